@@ -14,9 +14,10 @@
 use kvproto::kvrpcpb::IsolationLevel;
 
 use raftstore::store::engine::IterOption;
+use storage::mvcc::lock::{Lock, LockType};
 use storage::mvcc::write::{Write, WriteType};
-use storage::mvcc::Result;
-use storage::{Cursor, Key, ScanMode, Snapshot, Statistics, Value, CF_DEFAULT, CF_WRITE};
+use storage::mvcc::{Error, Result};
+use storage::{Cursor, Key, ScanMode, Snapshot, Statistics, Value, CF_DEFAULT, CF_LOCK, CF_WRITE};
 
 /// Build `IterOption` (which is later used to build `Cursor`) according to configurations.
 fn build_iter_opt(fill_cache: bool, prefix_filter: bool) -> IterOption {
@@ -34,6 +35,7 @@ pub struct PointGetterBuilder<S: Snapshot> {
     multi: bool,
     fill_cache: bool,
     omit_value: bool,
+    check_existence: bool,
     isolation_level: IsolationLevel,
 }
 
@@ -45,6 +47,7 @@ impl<S: Snapshot> PointGetterBuilder<S> {
             multi: true,
             fill_cache: true,
             omit_value: false,
+            check_existence: false,
             isolation_level: IsolationLevel::SI,
         }
     }
@@ -80,6 +83,18 @@ impl<S: Snapshot> PointGetterBuilder<S> {
         self
     }
 
+    /// Set whether the getter should only check for the existence of a live value, never
+    /// loading it. When `check_existence` is `true`, call `PointGetter::check_existence` instead
+    /// of `read_next`: it answers from the write CF alone, without ever creating a default
+    /// cursor.
+    ///
+    /// Defaults to `false`.
+    #[inline]
+    pub fn check_existence(mut self, check_existence: bool) -> Self {
+        self.check_existence = check_existence;
+        self
+    }
+
     /// Set the isolation level.
     ///
     /// Defaults to `IsolationLevel::SI`.
@@ -96,6 +111,7 @@ impl<S: Snapshot> PointGetterBuilder<S> {
             multi: self.multi,
             fill_cache: self.fill_cache,
             omit_value: self.omit_value,
+            check_existence: self.check_existence,
             isolation_level: self.isolation_level,
 
             statistics: Statistics::default(),
@@ -110,6 +126,27 @@ impl<S: Snapshot> PointGetterBuilder<S> {
             default_cursor: None,
         })
     }
+
+    /// Build a `BatchPointGetter` from the current configuration, to get many user keys in one
+    /// batch.
+    ///
+    /// Batch mode always walks a single shared cursor across many distinct key prefixes, which
+    /// is incompatible with the prefix bloom seek used when `multi == false`. So `multi` is
+    /// forced to `true` regardless of the value configured via `PointGetterBuilder::multi`.
+    pub fn build_batch(mut self) -> Result<BatchPointGetter<S>> {
+        assert!(
+            !self.check_existence,
+            "check_existence is not supported by BatchPointGetter"
+        );
+        self.multi = true;
+        let lock_cursor =
+            self.snapshot
+                .iter_cf(CF_LOCK, build_iter_opt(self.fill_cache, false), ScanMode::Forward)?;
+        Ok(BatchPointGetter {
+            inner: self.build()?,
+            lock_cursor,
+        })
+    }
 }
 
 /// This struct can be used to get the value of a user key. Internally, rollbacks are ignored and
@@ -127,6 +164,7 @@ pub struct PointGetter<S: Snapshot> {
     multi: bool,
     fill_cache: bool,
     omit_value: bool,
+    check_existence: bool,
     isolation_level: IsolationLevel,
 
     statistics: Statistics,
@@ -162,6 +200,66 @@ impl<S: Snapshot> PointGetter<S> {
             ts = super::util::load_and_check_lock(&self.snapshot, key, ts, &mut self.statistics)?;
         }
 
+        self.read_next_without_lock_check(key, ts)
+    }
+
+    /// Answer whether there is a live value for `key` at `ts`, without ever touching the
+    /// default CF. Requires the getter to have been built with
+    /// `PointGetterBuilder::check_existence(true)`.
+    ///
+    /// Like `omit_value`, this never seeks into the default CF; unlike `omit_value`, which still
+    /// builds and returns an empty value for a live key, no value is built at all: the first
+    /// `Put` returns `true` immediately, even when `short_value` is `None`, and a
+    /// `default_cursor` is never created. Combined with the `!multi` prefix-bloom seek, this turns a key-existence
+    /// probe into a single bloom-filtered write-CF seek with no data-CF I/O.
+    pub fn check_existence(&mut self, key: &Key, mut ts: u64) -> Result<bool> {
+        assert!(
+            self.check_existence,
+            "PointGetter must be built with `check_existence(true)` to call `check_existence`"
+        );
+
+        if !self.multi && self.read_once {
+            panic!("PointGetter(multi=false) must not call `check_existence` multiple times.");
+        }
+        self.read_once = true;
+
+        if self.isolation_level == IsolationLevel::SI {
+            ts = super::util::load_and_check_lock(&self.snapshot, key, ts, &mut self.statistics)?;
+        }
+
+        self.write_cursor
+            .near_seek(&key.append_ts(ts), &mut self.statistics.write)?;
+
+        loop {
+            if !self.write_cursor.valid() {
+                // Key space ended.
+                return Ok(false);
+            }
+            let write_key =
+                Key::from_encoded(self.write_cursor.key(&mut self.statistics.write).to_vec());
+            let user_key = write_key.truncate_ts()?;
+            if &user_key != key {
+                // Moved to another key.
+                return Ok(false);
+            }
+            let write = Write::parse(self.write_cursor.value(&mut self.statistics.write))?;
+            self.statistics.write.processed += 1;
+            self.write_cursor.next(&mut self.statistics.write);
+
+            match write.write_type {
+                WriteType::Put => return Ok(true),
+                WriteType::Delete => return Ok(false),
+                WriteType::Lock | WriteType::Rollback => {
+                    // Continue iterate next `write`.
+                }
+            }
+        }
+    }
+
+    /// Get the value of a user key at `ts`, assuming any conflicting lock has already been
+    /// checked by the caller. Used by `read_next` after its own lock check, and by
+    /// `BatchPointGetter`, which checks each key's lock through its shared lock cursor.
+    fn read_next_without_lock_check(&mut self, key: &Key, ts: u64) -> Result<Option<Value>> {
         // First seek to `${key}_${ts}`.
         self.write_cursor
             .near_seek(&key.append_ts(ts), &mut self.statistics.write)?;
@@ -211,9 +309,88 @@ impl<S: Snapshot> PointGetter<S> {
                     // Continue iterate next `write`.
                 }
             }
+        }
+    }
+
+    /// Get every version of a user key committed within the inclusive range
+    /// `[start_ts, end_ts]`, in descending commit order, as `(commit_ts, write_type, value)`
+    /// tuples.
+    ///
+    /// Unlike `read_next`, which stops at the newest visible `Put`/`Delete`, this keeps walking
+    /// the write CF and collects every `Put`/`Delete` record (resolving short vs. default-CF
+    /// values per entry) until the user key changes or a commit ts older than `start_ts` is
+    /// seen. `Lock` and `Rollback` records are skipped, same as `read_next`. When `omit_value`
+    /// is set, returned values are empty but timestamps and write types are still accurate.
+    ///
+    /// This enables CDC / incremental scans and "what did this key look like at time T"
+    /// debugging without a separate scanner.
+    pub fn read_history(
+        &mut self,
+        key: &Key,
+        start_ts: u64,
+        end_ts: u64,
+    ) -> Result<Vec<(u64, WriteType, Option<Value>)>> {
+        if !self.multi && self.read_once {
+            panic!("PointGetter(multi=false) must not call `read_history` multiple times.");
+        }
+        self.read_once = true;
+
+        let mut versions = Vec::new();
 
+        self.write_cursor
+            .near_seek(&key.append_ts(end_ts), &mut self.statistics.write)?;
+
+        loop {
+            if !self.write_cursor.valid() {
+                // Key space ended.
+                break;
+            }
+            let write_key =
+                Key::from_encoded(self.write_cursor.key(&mut self.statistics.write).to_vec());
+            let commit_ts = write_key.decode_ts()?;
+            let user_key = write_key.truncate_ts()?;
+            if &user_key != key {
+                // Moved to another key.
+                break;
+            }
+            if commit_ts < start_ts {
+                // Crossed the start of the requested range.
+                break;
+            }
+            let write = Write::parse(self.write_cursor.value(&mut self.statistics.write))?;
+            self.statistics.write.processed += 1;
             self.write_cursor.next(&mut self.statistics.write);
+
+            match write.write_type {
+                WriteType::Put => {
+                    let value = if self.omit_value {
+                        vec![]
+                    } else {
+                        match write.short_value {
+                            Some(value) => value,
+                            None => {
+                                self.ensure_default_cursor()?;
+                                super::util::load_data_by_write(
+                                    &mut self.default_cursor.as_mut().unwrap(),
+                                    key,
+                                    write,
+                                    &mut self.statistics,
+                                )?
+                            }
+                        }
+                    };
+                    versions.push((commit_ts, WriteType::Put, Some(value)));
+                }
+                WriteType::Delete => {
+                    versions.push((commit_ts, WriteType::Delete, None));
+                }
+                WriteType::Lock | WriteType::Rollback => {
+                    // Not a value-bearing version; continue to the next `write`.
+                }
+            }
         }
+
+        Ok(versions)
     }
 
     /// Create the default cursor if it doesn't exist.
@@ -228,4 +405,242 @@ impl<S: Snapshot> PointGetter<S> {
         self.default_cursor = Some(iter);
         Ok(())
     }
+}
+
+/// This struct can be used to get the values of many user keys in one batch. Compared to calling
+/// `PointGetter::read_next` once per key, it sorts the requested keys ascending first, then
+/// drives the shared `write_cursor` / `default_cursor` through them in a single forward pass,
+/// and checks each key's lock through one shared lock cursor instead of a fresh lock-CF lookup
+/// per key. This trades a small
+/// amount of bookkeeping (remembering where each key came from) for far fewer RocksDB seeks and
+/// bloom lookups when the keys are close together, e.g. when they come from the same region.
+///
+/// Batch mode behaves as if `multi` were always `true`; there is no way to use a prefix bloom
+/// seek across a batch of unrelated key prefixes.
+///
+/// Use `PointGetterBuilder::build_batch` to build a `BatchPointGetter`.
+pub struct BatchPointGetter<S: Snapshot> {
+    inner: PointGetter<S>,
+
+    /// Shared across the whole batch so that, once keys are visited in ascending order, lock
+    /// checks for the batch only ever seek this cursor forward, instead of doing one fresh
+    /// `CF_LOCK` lookup per key via `super::util::load_and_check_lock`.
+    lock_cursor: Cursor<S::Iter>,
+}
+
+impl<S: Snapshot> BatchPointGetter<S> {
+    /// Take out and reset the statistics collected so far.
+    #[inline]
+    pub fn take_statistics(&mut self) -> Statistics {
+        self.inner.take_statistics()
+    }
+
+    /// Get the values of `keys` at `ts`. The returned `Vec` is in the same order as `keys`,
+    /// regardless of the order in which keys are actually looked up internally.
+    pub fn batch_read(&mut self, keys: &[Key], ts: u64) -> Result<Vec<Option<Value>>> {
+        // Remember each key's original position, then sort ascending so that the shared cursors
+        // only ever move forward.
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+
+        let mut results: Vec<Option<Value>> = vec![None; keys.len()];
+        let mut prev_idx: Option<usize> = None;
+        for &idx in &order {
+            let key = &keys[idx];
+            if let Some(prev) = prev_idx {
+                if keys[prev] == *key {
+                    // A repeated key: reuse its result instead of seeking the cursors back to it.
+                    results[idx] = results[prev].clone();
+                    continue;
+                }
+            }
+            let read_ts = if self.inner.isolation_level == IsolationLevel::SI {
+                self.check_lock(key, ts)?
+            } else {
+                ts
+            };
+            results[idx] = self.inner.read_next_without_lock_check(key, read_ts)?;
+            prev_idx = Some(idx);
+        }
+        Ok(results)
+    }
+
+    /// Check for a conflicting lock on `key` at `ts` using the batch's shared `lock_cursor`,
+    /// mirroring the semantics of `super::util::load_and_check_lock` (a lock written after `ts`,
+    /// or one of type `LockType::Lock`, does not block the read; reading our own primary lock at
+    /// the maximum ts is allowed to see the version just before the lock was taken). Keys must be
+    /// visited in ascending order, as `batch_read` does, so the cursor never has to seek backward.
+    fn check_lock(&mut self, key: &Key, ts: u64) -> Result<u64> {
+        self.lock_cursor
+            .near_seek(key, &mut self.inner.statistics.lock)?;
+        if !self.lock_cursor.valid()
+            || self.lock_cursor.key(&mut self.inner.statistics.lock) != key.as_encoded().as_slice()
+        {
+            // No lock on this key.
+            return Ok(ts);
+        }
+
+        let lock = Lock::parse(self.lock_cursor.value(&mut self.inner.statistics.lock))?;
+        self.inner.statistics.lock.processed += 1;
+
+        if lock.ts > ts || lock.lock_type == LockType::Lock {
+            // The lock was written after the snapshot we are reading, or it is a pure `Lock`
+            // record (e.g. a pessimistic lock) that does not block reads.
+            return Ok(ts);
+        }
+
+        let raw_key = key.to_raw()?;
+        if ts == ::std::u64::MAX && raw_key == lock.primary {
+            // Reading the latest version through our own primary lock is allowed; fall back to
+            // just before the lock was taken.
+            return Ok(lock.ts - 1);
+        }
+
+        Err(Error::KeyIsLocked {
+            key: raw_key,
+            primary: lock.primary,
+            ts: lock.ts,
+            ttl: lock.ttl,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kvproto::kvrpcpb::{Context, IsolationLevel};
+
+    use storage::engine::{Engine, TestEngineBuilder};
+    use storage::mvcc::tests::{must_commit, must_prewrite_delete, must_prewrite_put};
+    use storage::mvcc::write::WriteType;
+    use storage::Key;
+
+    use super::{BatchPointGetter, PointGetter, PointGetterBuilder};
+
+    fn new_point_getter<E: Engine>(engine: &E) -> PointGetter<E::Snap> {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        PointGetterBuilder::new(snapshot)
+            .isolation_level(IsolationLevel::SI)
+            .build()
+            .unwrap()
+    }
+
+    fn new_existence_point_getter<E: Engine>(engine: &E) -> PointGetter<E::Snap> {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        PointGetterBuilder::new(snapshot)
+            .isolation_level(IsolationLevel::SI)
+            .check_existence(true)
+            .build()
+            .unwrap()
+    }
+
+    fn new_batch_point_getter<E: Engine>(engine: &E) -> BatchPointGetter<E::Snap> {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        PointGetterBuilder::new(snapshot)
+            .isolation_level(IsolationLevel::SI)
+            .build_batch()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_history_is_descending_and_bounds_are_inclusive() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        must_prewrite_put(&engine, b"foo", b"v1", b"foo", 10);
+        must_commit(&engine, b"foo", 10, 10);
+        must_prewrite_delete(&engine, b"foo", b"foo", 20);
+        must_commit(&engine, b"foo", 20, 20);
+        must_prewrite_put(&engine, b"foo", b"v3", b"foo", 30);
+        must_commit(&engine, b"foo", 30, 30);
+
+        let mut getter = new_point_getter(&engine);
+        let versions = getter
+            .read_history(&Key::from_raw(b"foo"), 10, 30)
+            .unwrap();
+
+        // Both `start_ts` and `end_ts` are inclusive, and versions come back newest-first.
+        assert_eq!(
+            versions,
+            vec![
+                (30, WriteType::Put, Some(b"v3".to_vec())),
+                (20, WriteType::Delete, None),
+                (10, WriteType::Put, Some(b"v1".to_vec())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_existence() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        must_prewrite_put(&engine, b"short", b"v", b"short", 10);
+        must_commit(&engine, b"short", 10, 20);
+        // A value long enough that it is stored in the default CF rather than as a short value.
+        let long_value = vec![b'v'; 4096];
+        must_prewrite_put(&engine, b"long", &long_value, b"long", 10);
+        must_commit(&engine, b"long", 10, 20);
+        must_prewrite_delete(&engine, b"deleted", b"deleted", 10);
+        must_commit(&engine, b"deleted", 10, 20);
+
+        let mut getter = new_existence_point_getter(&engine);
+        assert_eq!(
+            getter.check_existence(&Key::from_raw(b"short"), 30).unwrap(),
+            true
+        );
+
+        let mut getter = new_existence_point_getter(&engine);
+        assert_eq!(
+            getter.check_existence(&Key::from_raw(b"long"), 30).unwrap(),
+            true
+        );
+
+        let mut getter = new_existence_point_getter(&engine);
+        assert_eq!(
+            getter
+                .check_existence(&Key::from_raw(b"deleted"), 30)
+                .unwrap(),
+            false
+        );
+
+        let mut getter = new_existence_point_getter(&engine);
+        assert_eq!(
+            getter
+                .check_existence(&Key::from_raw(b"missing"), 30)
+                .unwrap(),
+            false
+        );
+    }
+
+    #[test]
+    fn test_batch_read_returns_values_in_request_order() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        must_prewrite_put(&engine, b"bar", b"2", b"bar", 10);
+        must_commit(&engine, b"bar", 10, 20);
+        must_prewrite_put(&engine, b"foo", b"1", b"foo", 10);
+        must_commit(&engine, b"foo", 10, 20);
+
+        let mut getter = new_batch_point_getter(&engine);
+        // Requested out of key order, on purpose: `foo` sorts after `bar`.
+        let keys = vec![Key::from_raw(b"foo"), Key::from_raw(b"bar")];
+        let values = getter.batch_read(&keys, 30).unwrap();
+
+        assert_eq!(values, vec![Some(b"1".to_vec()), Some(b"2".to_vec())]);
+    }
+
+    #[test]
+    fn test_batch_read_handles_duplicate_and_missing_keys() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        must_prewrite_put(&engine, b"foo", b"1", b"foo", 10);
+        must_commit(&engine, b"foo", 10, 20);
+
+        let mut getter = new_batch_point_getter(&engine);
+        let keys = vec![
+            Key::from_raw(b"foo"),
+            Key::from_raw(b"missing"),
+            Key::from_raw(b"foo"),
+        ];
+        let values = getter.batch_read(&keys, 30).unwrap();
+
+        assert_eq!(
+            values,
+            vec![Some(b"1".to_vec()), None, Some(b"1".to_vec())]
+        );
+    }
 }
\ No newline at end of file